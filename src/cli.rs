@@ -1,12 +1,21 @@
 //! Command Line Interface functions
 
 use clap::{Parser, Subcommand};
+use colored::{Color, Colorize};
 use futures::future::join_all;
 use itertools::Itertools;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::client::{StarlingAccount, Transaction};
+use crate::client::{Balance, ClientConfig, Currency, Money, StarlingAccount, Transaction};
+use crate::error::StarlingError;
 use crate::persist;
+use crate::report::{self, SpendTotal, WeekStart};
+use crate::summary::{summarize, Summary};
+use crate::theme::{BuiltinTheme, Theme};
 
 /// CLI arguments
 #[derive(Parser, Debug, Clone)]
@@ -14,6 +23,65 @@ use crate::persist;
 pub struct Args {
     #[clap(subcommand)]
     pub command: Command,
+
+    /// Show raw minor units alongside every total
+    #[clap(long, global = true)]
+    pub audit: bool,
+
+    /// Output JSON
+    #[clap(long, global = true)]
+    pub json: bool,
+
+    /// Disable coloured output
+    #[clap(long, global = true)]
+    pub no_color: bool,
+
+    /// Directory holding API tokens
+    #[clap(
+        long,
+        global = true,
+        env = "STARLING_CONFIG_DIR",
+        default_value = ".",
+        parse(from_os_str)
+    )]
+    pub config_dir: PathBuf,
+
+    /// Directory holding stored transactions
+    #[clap(
+        long,
+        global = true,
+        env = "STARLING_DATA_DIR",
+        default_value = ".",
+        parse(from_os_str)
+    )]
+    pub data_dir: PathBuf,
+
+    /// Seconds allowed to connect to the API
+    #[clap(long, global = true, default_value_t = 10)]
+    pub api_timeout_connect: u64,
+
+    /// Seconds allowed for a whole API request
+    #[clap(long, global = true, default_value_t = 30)]
+    pub api_timeout: u64,
+
+    /// Colour theme, adjusted by any colours set in theme.yml in the config directory
+    #[clap(long, global = true, arg_enum, default_value = "default")]
+    pub theme: BuiltinTheme,
+
+    /// Drop transactions the API returns from outside the requested window. Settlement timing
+    /// means some just outside are real spending, which is then missed from reports
+    #[clap(long, global = true)]
+    pub strict_window: bool,
+}
+
+impl Args {
+    /// API settings from the global flags.
+    pub fn client_config(&self) -> ClientConfig {
+        ClientConfig::default()
+            .connect_timeout(Duration::from_secs(self.api_timeout_connect))
+            .timeout(Duration::from_secs(self.api_timeout))
+            .strict_window(self.strict_window)
+    }
 }
 
 /// CLI Commands
@@ -27,26 +95,827 @@ pub enum Command {
         //// Days to get
         #[clap(short, long, default_value_t = 7)]
         days: i64,
+
+        /// Copy Starling notes into empty local notes (never overwrites)
+        #[clap(long)]
+        seed_notes: bool,
+    },
+
+    /// Weekly totals
+    Weekly {
+        /// Days to get, extended back to the start of the first week. The current week is
+        /// still in progress.
+        #[clap(short, long, default_value_t = 28)]
+        days: i64,
+
+        /// Day the week starts on
+        #[clap(short, long, arg_enum, default_value = "monday")]
+        week_start: WeekStart,
+    },
+
+    /// Top merchants by money out
+    Merchants {
+        //// Days to get
+        #[clap(short, long, default_value_t = 28)]
+        days: i64,
+
+        /// Roll merchants whose total is below this amount into a single "Other" row
+        #[clap(long, parse(try_from_str = report::parse_amount))]
+        aggregate_below: Option<i64>,
+    },
+
+    /// Money out per spending category
+    Categories {
+        //// Days to get
+        #[clap(short, long, default_value_t = 28)]
+        days: i64,
+
+        /// Draw a bar chart of each category's share of money out
+        #[clap(long)]
+        chart: bool,
+
+        /// Roll categories whose total is below this amount into a single "Other" row
+        #[clap(long, parse(try_from_str = report::parse_amount))]
+        aggregate_below: Option<i64>,
+    },
+
+    /// Most recent transactions
+    Tail {
+        /// Number of transactions to show
+        #[clap(default_value_t = 10)]
+        n: usize,
+    },
+
+    /// Show transactions by uid
+    GetMany {
+        /// Transaction uids
+        #[clap(required = true)]
+        uids: Vec<String>,
+    },
+
+    /// Rename a stored profile
+    RenameProfile {
+        /// Current profile name
+        from: String,
+
+        /// New profile name
+        to: String,
     },
 }
 
-pub async fn do_update(accounts: &[StarlingAccount], days: i64) {
-    // Fetch transactions from all Starling accounts and sort by date.
-    let new_transactions = join_all(
+/// Initial lookback for `Tail`, doubled until enough transactions are found.
+const TAIL_INITIAL_DAYS: i64 = 7;
+
+/// Furthest `Tail` will look back.
+const TAIL_MAX_DAYS: i64 = 365;
+
+/// Fetch settled transactions from all Starling accounts and sort by date.
+async fn fetch_settled(accounts: &[StarlingAccount], since: chrono::Duration) -> Vec<Transaction> {
+    let (transactions, errors) = try_fetch_settled(accounts, since).await;
+    report_errors(errors);
+    transactions.unwrap_or_default()
+}
+
+/// Fetch settled transactions from all Starling accounts and sort by date, or `None` if every
+/// account's request failed, along with the errors from the accounts that failed.
+async fn try_fetch_settled(
+    accounts: &[StarlingAccount],
+    since: chrono::Duration,
+) -> (Option<Vec<Transaction>>, Vec<StarlingError>) {
+    let results = join_all(
         accounts
             .iter()
-            .map(|a| a.settled_transactions_between(chrono::Duration::days(days)))
+            .map(|a| a.settled_transactions_between(since))
             .collect::<Vec<_>>(),
     )
     .await;
 
-    let new_transactions: Vec<_> = new_transactions.into_iter().flatten().sorted().collect();
+    let all_failed = !results.is_empty() && results.iter().all(Result::is_err);
+    let (transactions, errors): (Vec<_>, Vec<_>) = results.into_iter().partition_result();
+    let transactions = transactions
+        .into_iter()
+        .flatten()
+        .sorted_by(|a, b| a.sort_key().cmp(&b.sort_key()))
+        .collect();
+    ((!all_failed).then_some(transactions), errors)
+}
+
+/// Print a failed request's error and carry on without it.
+fn report_error<T>(result: Result<T, StarlingError>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            None
+        }
+    }
+}
+
+/// Print the errors from failed requests.
+fn report_errors(errors: Vec<StarlingError>) {
+    for e in errors {
+        eprintln!("ERROR: {}", e);
+    }
+}
+
+/// Fetch the newest `n` settled transactions, newest first.
+///
+/// The lookback window is widened until it holds at least `n` transactions, so quiet accounts
+/// still fill the list, up to `TAIL_MAX_DAYS`. Errors are reported once, from the final window.
+async fn fetch_latest(accounts: &[StarlingAccount], n: usize) -> Vec<Transaction> {
+    let (transactions, errors) = latest(n, |days| {
+        try_fetch_settled(accounts, chrono::Duration::days(days))
+    })
+    .await;
+    report_errors(errors);
+    transactions
+}
+
+/// The newest `n` transactions from `fetch`, which gets date-sorted transactions from the last
+/// given number of days, or `None` if nothing could be fetched, along with any errors. Gives up
+/// widening the window when a fetch fails entirely.
+///
+/// Only the final fetch's errors are returned, so an account that keeps failing is reported
+/// once rather than for every window tried.
+async fn latest<F, Fut, E>(n: usize, mut fetch: F) -> (Vec<Transaction>, Vec<E>)
+where
+    F: FnMut(i64) -> Fut,
+    Fut: Future<Output = (Option<Vec<Transaction>>, Vec<E>)>,
+{
+    let mut days = TAIL_INITIAL_DAYS;
+    loop {
+        let (transactions, errors) = match fetch(days).await {
+            (Some(transactions), errors) => (transactions, errors),
+            (None, errors) => return (Vec::new(), errors),
+        };
+        if transactions.len() >= n || days >= TAIL_MAX_DAYS {
+            return (transactions.into_iter().rev().take(n).collect(), errors);
+        }
+        days = (days * 2).min(TAIL_MAX_DAYS);
+    }
+}
+
+/// Print a summary, with raw minor units if auditing.
+fn print_summary(summary: &Summary, audit: bool) {
+    if audit {
+        println!("{:#}", summary);
+    } else {
+        println!("{}", summary);
+    }
+}
+
+/// Print a value as pretty JSON.
+fn print_json<T: Serialize>(value: &T) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).expect("Couldn't serialise JSON")
+    );
+}
+
+/// A row of the balances table
+#[derive(Serialize, Debug)]
+struct BalanceRow {
+    account: String,
+    #[serde(rename = "accountType")]
+    account_type: String,
+    currency: Currency,
+    #[serde(flatten)]
+    balance: Balance,
+}
+
+pub async fn do_balances(accounts: &[StarlingAccount], json: bool, audit: bool, theme: &Theme) {
+    // Fetch concurrently, but render only once everything has arrived.
+    let balances = join_all(accounts.iter().map(|a| a.balance()).collect::<Vec<_>>()).await;
+
+    let mut rows: Vec<_> = accounts
+        .iter()
+        .zip(balances)
+        .filter_map(|(a, balance)| {
+            Some(BalanceRow {
+                account: a.detail.name.clone(),
+                account_type: a.detail.account_type.clone(),
+                currency: a.detail.currency,
+                balance: report_error(balance)?,
+            })
+        })
+        .collect();
+    sort_balance_rows(&mut rows);
+
+    if json {
+        print_json(&rows);
+    } else {
+        print!("{}", balances_table(&rows, audit, theme));
+    }
+}
+
+/// Order balances by account name.
+fn sort_balance_rows(rows: &mut [BalanceRow]) {
+    rows.sort_by(|a, b| a.account.cmp(&b.account));
+}
+
+/// Render balances as an aligned table.
+fn balances_table(rows: &[BalanceRow], audit: bool, theme: &Theme) -> String {
+    let header = ["ACCOUNT", "TYPE", "CURRENCY", "CLEARED", "EFFECTIVE"];
+    let cells: Vec<[String; 5]> = rows
+        .iter()
+        .map(|r| {
+            [
+                r.account.clone(),
+                r.account_type.clone(),
+                format!("{:?}", r.currency),
+                format_money(&r.balance.cleared, audit),
+                format_money(&r.balance.effective, audit),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(str::len);
+    for row in cells.iter() {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    // Text columns are left aligned, money columns right aligned.
+    let line = |row: [&str; 5]| {
+        format!(
+            "{:<w0$}  {:<w1$}  {:<w2$}  {:>w3$}  {:>w4$}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+            w4 = widths[4],
+        )
+    };
+
+    let mut table = format!("{}\n", theme.header(&line(header)));
+    for row in cells.iter() {
+        table.push_str(&line(row.each_ref().map(String::as_str)));
+        table.push('\n');
+    }
+    table
+}
+
+pub async fn do_update(
+    accounts: &[StarlingAccount],
+    days: i64,
+    seed_notes: bool,
+    audit: bool,
+    json: bool,
+    data_dir: &Path,
+    theme: &Theme,
+) {
+    let new_transactions = fetch_settled(accounts, chrono::Duration::days(days)).await;
+    let summary = summarize(&new_transactions);
 
     // Display.
-    for transaction in new_transactions.iter() {
-        println!("{}", transaction.to_string());
+    if json {
+        print_json(&serde_json::json!({
+            "transactions": new_transactions,
+            "summary": summary,
+        }));
+    } else {
+        for transaction in new_transactions.iter() {
+            println!("{}", theme.format_transaction(transaction));
+        }
+        print_summary(&summary, audit);
+    }
+
+    if seed_notes {
+        let mut notes = persist::Notes::load(data_dir);
+        let seeded = notes.seed(&new_transactions);
+        notes.save(data_dir);
+        if !json {
+            println!("Seeded {} notes", seeded);
+        }
+    }
+
+    if !json {
+        println!("Writing transactions to file system");
+    }
+    persist::update_transactions(new_transactions, data_dir);
+    if !json {
+        println!("Done")
+    }
+}
+
+pub async fn do_weekly(
+    accounts: &[StarlingAccount],
+    days: i64,
+    week_start: WeekStart,
+    audit: bool,
+    json: bool,
+    theme: &Theme,
+) {
+    let now = chrono::Utc::now();
+    let start = report::week_aligned_start(now, days, week_start);
+    let transactions = fetch_settled(accounts, now - start).await;
+    let weeks: Vec<_> = report::by_week(transactions, week_start)
+        .into_iter()
+        .map(|(week, transactions)| (week, summarize(&transactions)))
+        .collect();
+
+    if json {
+        let weeks: Vec<_> = weeks
+            .iter()
+            .map(|(week, summary)| serde_json::json!({ "week": week, "summary": summary }))
+            .collect();
+        print_json(&weeks);
+        return;
+    }
+
+    for (week, summary) in weeks.iter() {
+        let title = format!("Week of {}", week.format("%Y-%m-%d (%a)"));
+        println!("{}", theme.header(&title));
+        print_summary(summary, audit);
+    }
+}
+
+pub async fn do_merchants(
+    accounts: &[StarlingAccount],
+    days: i64,
+    aggregate_below: Option<i64>,
+    audit: bool,
+    json: bool,
+) {
+    let transactions = fetch_settled(accounts, chrono::Duration::days(days)).await;
+
+    let mut totals = report::merchant_totals(&transactions);
+    if let Some(threshold) = aggregate_below {
+        totals = report::aggregate_below(totals, threshold, ("merchant", "merchants"));
+    }
+
+    if json {
+        print_totals_json(&totals);
+    } else if totals.is_empty() {
+        println!("No spending");
+    } else {
+        print_totals(&totals, audit);
+    }
+}
+
+pub async fn do_categories(
+    accounts: &[StarlingAccount],
+    days: i64,
+    chart: bool,
+    aggregate_below: Option<i64>,
+    audit: bool,
+    json: bool,
+) {
+    let transactions = fetch_settled(accounts, chrono::Duration::days(days)).await;
+    let mut totals = report::category_totals(&transactions);
+    if let Some(threshold) = aggregate_below {
+        totals = report::aggregate_below(totals, threshold, ("category", "categories"));
+    }
+
+    if json {
+        print_totals_json(&totals);
+    } else if totals.is_empty() {
+        println!("No spending");
+    } else if chart {
+        let colorize = colored::control::SHOULD_COLORIZE.should_colorize();
+        print!("{}", bar_chart(&totals, terminal_width(), audit, colorize));
+    } else {
+        print_totals(&totals, audit);
+    }
+}
+
+/// Print named totals and their grand totals per currency as JSON.
+fn print_totals_json(totals: &[SpendTotal]) {
+    print_json(&serde_json::json!({
+        "totals": totals,
+        "grandTotals": report::grand_totals(totals),
+    }));
+}
+
+/// Format money, with raw minor units if auditing.
+fn format_money(money: &Money, audit: bool) -> String {
+    if audit {
+        format!("{:#}", money)
+    } else {
+        money.to_string()
+    }
+}
+
+/// Print named totals as an aligned list with a grand total per currency.
+fn print_totals(totals: &[SpendTotal], audit: bool) {
+    let width = totals
+        .iter()
+        .map(|m| m.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    for total in totals.iter() {
+        println!(
+            "{:<width$}  {:>4}  {}",
+            total.name,
+            total.count,
+            format_money(&total.total, audit),
+            width = width
+        );
     }
 
-    persist::update_transactions(new_transactions);
-    println!("Done")
+    for total in report::grand_totals(totals) {
+        println!("Total {}", format_money(&total, audit));
+    }
+}
+
+/// Terminal width used when it can't be determined.
+const DEFAULT_TERMINAL_WIDTH: usize = 80;
+
+/// Narrowest bar section drawn, however small the terminal.
+const MIN_BAR_WIDTH: usize = 10;
+
+/// Terminal width: `COLUMNS` if set, otherwise the size of the controlling tty.
+///
+/// Shells don't usually export `COLUMNS`, so it's only an override.
+fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .or_else(tty_width)
+        .unwrap_or(DEFAULT_TERMINAL_WIDTH)
+}
+
+/// Columns of the controlling tty, as reported by `stty size`.
+fn tty_width() -> Option<usize> {
+    let tty = std::fs::File::open("/dev/tty").ok()?;
+    let output = std::process::Command::new("stty")
+        .arg("size")
+        .stdin(tty)
+        .stderr(std::process::Stdio::null())
+        .output()
+        .ok()?;
+    parse_stty_size(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Columns from `stty size` output of the form "rows columns".
+fn parse_stty_size(output: &str) -> Option<usize> {
+    match output.split_whitespace().collect::<Vec<_>>()[..] {
+        [_, columns] => columns.parse().ok().filter(|&c| c > 0),
+        _ => None,
+    }
+}
+
+/// Render totals as horizontal bars proportional to their share of money out in their currency.
+///
+/// Bars are coloured when `colorize` is set; otherwise successive bars use different shading.
+fn bar_chart(totals: &[SpendTotal], width: usize, audit: bool, colorize: bool) -> String {
+    const COLORS: [Color; 6] = [
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+        Color::Yellow,
+        Color::Green,
+        Color::Red,
+    ];
+    const SHADES: [char; 4] = ['█', '▓', '▒', '░'];
+
+    let grand_totals = report::grand_totals(totals);
+    let amounts: Vec<_> = totals
+        .iter()
+        .map(|t| format_money(&t.total, audit))
+        .collect();
+    let name_width = totals
+        .iter()
+        .map(|t| t.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let amount_width = amounts.iter().map(String::len).max().unwrap_or(0);
+    // name, bar, "100.0%" and amount, separated by two spaces.
+    let bar_width = width
+        .saturating_sub(name_width + amount_width + 6 + 3 * 2)
+        .max(MIN_BAR_WIDTH);
+
+    let mut chart = String::new();
+    for (i, (total, amount)) in totals.iter().zip(amounts).enumerate() {
+        let currency_total = grand_totals
+            .iter()
+            .find(|m| m.currency == total.total.currency)
+            .map_or(0, |m| m.minor_units);
+        let (len, percent) = match currency_total {
+            0 => (0, 0.0),
+            n => (
+                bar_len(total.total.minor_units, n, bar_width),
+                100.0 * total.total.minor_units as f64 / n as f64,
+            ),
+        };
+
+        let bar = if colorize {
+            "█".repeat(len).color(COLORS[i % COLORS.len()]).to_string()
+        } else {
+            SHADES[i % SHADES.len()].to_string().repeat(len)
+        };
+        chart.push_str(&format!(
+            "{:<name_width$}  {}{}  {:>5.1}%  {:>amount_width$}\n",
+            total.name,
+            bar,
+            " ".repeat(bar_width - len),
+            percent,
+            amount,
+            name_width = name_width,
+            amount_width = amount_width,
+        ));
+    }
+    chart
+}
+
+/// Length of a bar for `part` of `whole`, out of `width` characters, rounded to nearest.
+fn bar_len(part: i64, whole: i64, width: usize) -> usize {
+    let width = width as i64;
+    ((part * width + whole / 2) / whole).clamp(0, width) as usize
+}
+
+pub async fn do_tail(accounts: &[StarlingAccount], n: usize, json: bool, theme: &Theme) {
+    let transactions = fetch_latest(accounts, n).await;
+    if json {
+        print_json(&transactions);
+        return;
+    }
+
+    for transaction in transactions.iter() {
+        println!("{}", theme.format_transaction(transaction));
+    }
+}
+
+/// Print the transactions with the given uids. Uids that weren't found, or couldn't be looked
+/// up, are reported on stderr, so `--json` output stays parseable.
+pub async fn do_get_many(accounts: &[StarlingAccount], uids: &[String], json: bool, theme: &Theme) {
+    // Look for each uid in every account until it's found.
+    let mut missing = uids.to_vec();
+    let mut found = Vec::new();
+    let mut failed = HashMap::new();
+    for account in accounts {
+        if missing.is_empty() {
+            break;
+        }
+        let wanted: Vec<_> = missing.iter().map(String::as_str).collect();
+        let lookup = account.transactions_by_uids(&wanted).await;
+        found.extend(lookup.found);
+        // A uid that failed here may still be found in a later account.
+        missing = lookup.missing;
+        for (uid, e) in lookup.failed {
+            missing.push(uid.clone());
+            failed.insert(uid, e);
+        }
+    }
+
+    found.sort_by(|a, b| a.sort_key().cmp(&b.sort_key()));
+    if json {
+        print_json(&found);
+    } else {
+        for transaction in found.iter() {
+            println!("{}", theme.format_transaction(transaction));
+        }
+    }
+    for uid in uids.iter().filter(|uid| missing.contains(uid)) {
+        match failed.get(uid) {
+            Some(e) => eprintln!("ERROR: {}: {}", uid, e),
+            None => eprintln!("Not found: {}", uid),
+        }
+    }
+}
+
+pub fn do_rename_profile(tokens_file: &Path, from: &str, to: &str) {
+    let mut tokens = persist::Tokens::new(tokens_file);
+    if let Err(e) = tokens.rename(from, to) {
+        eprintln!("ERROR: {}", e);
+        std::process::exit(1);
+    }
+    tokens.save(tokens_file);
+    println!("Renamed profile '{}' to '{}'", from, to);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::testing::transaction;
+    use crate::theme::testing::with_color;
+
+    /// Transactions forty, ten and one days old, oldest first.
+    fn quiet_account() -> Vec<(i64, Transaction)> {
+        [(40, "2021-12-22"), (10, "2022-01-21"), (1, "2022-01-30")]
+            .iter()
+            .map(|(age, date)| {
+                let time = format!("{}T12:00:00Z", date);
+                (
+                    *age,
+                    transaction(&format!("age-{}", age), &time, "OUT", 100, "GBP"),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn tail_widens_lookback_until_enough_found() {
+        let mut requested = Vec::new();
+        let (latest, _) = latest(3, |days| {
+            requested.push(days);
+            let found = quiet_account()
+                .into_iter()
+                .filter(|(age, _)| *age <= days)
+                .map(|(_, t)| t)
+                .collect();
+            async move { (Some(found), Vec::<String>::new()) }
+        })
+        .await;
+
+        assert_eq!(requested, vec![7, 14, 28, 56]);
+        let uids: Vec<_> = latest.iter().map(|t| t.uid.as_str()).collect();
+        assert_eq!(uids, vec!["age-1", "age-10", "age-40"]);
+    }
+
+    #[tokio::test]
+    async fn tail_stops_at_max_lookback() {
+        let mut requested = Vec::new();
+        let (latest, _) = latest(10, |days| {
+            requested.push(days);
+            async { (Some(Vec::new()), Vec::<String>::new()) }
+        })
+        .await;
+
+        assert!(latest.is_empty());
+        assert_eq!(requested.last(), Some(&TAIL_MAX_DAYS));
+    }
+
+    #[tokio::test]
+    async fn tail_stops_widening_when_fetch_fails() {
+        let mut requested = Vec::new();
+        let (latest, errors) = latest(10, |days| {
+            requested.push(days);
+            async { (None, vec!["down".to_string()]) }
+        })
+        .await;
+
+        assert!(latest.is_empty());
+        assert_eq!(errors, vec!["down"]);
+        assert_eq!(requested, vec![TAIL_INITIAL_DAYS]);
+    }
+
+    #[tokio::test]
+    async fn tail_reports_a_failing_account_once() {
+        // One account keeps failing while the other has too few transactions.
+        let mut requested = Vec::new();
+        let (latest, errors) = latest(10, |days| {
+            requested.push(days);
+            let errors = vec![format!("failed over {} days", days)];
+            async { (Some(Vec::new()), errors) }
+        })
+        .await;
+
+        assert!(latest.is_empty());
+        assert!(requested.len() > 1);
+        assert_eq!(errors, vec![format!("failed over {} days", TAIL_MAX_DAYS)]);
+    }
+
+    /// Remove ANSI colour codes, so tests don't depend on whether colour is enabled.
+    fn strip_ansi(s: &str) -> String {
+        let mut out = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            if c == '\u{1b}' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    fn balance_row(account: &str, cleared: i64, effective: i64) -> BalanceRow {
+        BalanceRow {
+            account: account.to_string(),
+            account_type: "PRIMARY".to_string(),
+            currency: Currency::GBP,
+            balance: Balance {
+                cleared: Money::new(cleared, Currency::GBP),
+                effective: Money::new(effective, Currency::GBP),
+            },
+        }
+    }
+
+    #[test]
+    fn balances_table_is_sorted_and_aligned() {
+        let mut rows = vec![
+            balance_row("Zebra business account", 5, -125000),
+            balance_row("Bills", 1234567, 1234000),
+        ];
+        sort_balance_rows(&mut rows);
+
+        let table = strip_ansi(&balances_table(&rows, false, &Theme::default()));
+        let lines: Vec<_> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("ACCOUNT"));
+        assert!(lines[1].starts_with("Bills "));
+        assert!(lines[2].starts_with("Zebra business account "));
+
+        // Every line is the same width, with columns starting at the same offsets.
+        assert!(lines.iter().all(|l| l.len() == lines[0].len()));
+        for column in ["TYPE", "CURRENCY"] {
+            let offset = lines[0].find(column).expect("Missing column");
+            assert!(lines[1..].iter().all(|l| &l[offset - 2..offset] == "  "));
+        }
+        // Money is right aligned.
+        assert!(lines[1].ends_with("12340.00 GBP"));
+        assert!(lines[2].ends_with("-1250.00 GBP"));
+    }
+
+    #[test]
+    fn balances_table_audit_shows_minor_units() {
+        let table = strip_ansi(&balances_table(
+            &[balance_row("Bills", 505, -505)],
+            true,
+            &Theme::default(),
+        ));
+        assert!(table.contains("5.05 GBP (505)"));
+        assert!(table.contains("-5.05 GBP (-505)"));
+    }
+
+    fn spend_total(name: &str, minor_units: i64) -> SpendTotal {
+        SpendTotal {
+            name: name.to_string(),
+            total: Money::new(minor_units, Currency::GBP),
+            count: 1,
+        }
+    }
+
+    /// Length of the bar on each line of an uncoloured chart.
+    fn bar_lengths(chart: &str) -> Vec<usize> {
+        chart
+            .lines()
+            .map(|l| l.chars().filter(|c| "█▓▒░".contains(*c)).count())
+            .collect()
+    }
+
+    #[test]
+    fn bar_chart_bars_are_proportional() {
+        let totals = [
+            spend_total("Groceries", 3000),
+            spend_total("Transport", 1000),
+        ];
+        // Leaves a 52 character bar section after the name, percentage and amount.
+        let chart = bar_chart(&totals, 82, false, false);
+
+        assert_eq!(bar_lengths(&chart), vec![39, 13]);
+        assert!(chart.lines().all(|l| l.chars().count() == 82));
+        assert!(chart.contains(" 75.0%"));
+        assert!(chart.contains(" 25.0%"));
+    }
+
+    #[test]
+    fn bar_chart_single_category_fills_the_bar() {
+        let chart = bar_chart(&[spend_total("Groceries", 3000)], 40, false, false);
+
+        assert_eq!(chart.lines().count(), 1);
+        assert_eq!(
+            bar_lengths(&chart),
+            vec![40 - "Groceries".len() - "30.00 GBP".len() - 12]
+        );
+        assert!(chart.contains("100.0%"));
+    }
+
+    #[test]
+    fn bar_chart_narrow_terminal_keeps_minimum_bar() {
+        let chart = bar_chart(&[spend_total("Groceries", 3000)], 10, false, false);
+        assert_eq!(bar_lengths(&chart), vec![MIN_BAR_WIDTH]);
+    }
+
+    #[test]
+    fn bar_chart_empty_is_blank() {
+        assert_eq!(bar_chart(&[], 80, false, false), "");
+    }
+
+    #[test]
+    fn bar_chart_colours_bars_when_asked() {
+        let chart = with_color(|| bar_chart(&[spend_total("Groceries", 3000)], 40, false, true));
+
+        assert!(chart.contains('\u{1b}'));
+        assert!(!chart.contains('▓'));
+    }
+
+    #[test]
+    fn stty_size_parsing() {
+        assert_eq!(parse_stty_size("24 132\n"), Some(132));
+        assert_eq!(parse_stty_size("24 0\n"), None);
+        assert_eq!(parse_stty_size(""), None);
+        assert_eq!(parse_stty_size("garbage"), None);
+    }
+
+    #[test]
+    fn balance_row_json_keys_match_api_style() {
+        assert_eq!(
+            serde_json::to_value(balance_row("Bills", 505, -505)).expect("Couldn't serialise row"),
+            serde_json::json!({
+                "account": "Bills",
+                "accountType": "PRIMARY",
+                "currency": "GBP",
+                "clearedBalance": { "minorUnits": 505, "currency": "GBP" },
+                "effectiveBalance": { "minorUnits": -505, "currency": "GBP" },
+            })
+        );
+    }
 }
@@ -1,14 +1,22 @@
 //! Starling account model
 
+use crate::error::StarlingError;
 use crate::persist::ApiKey;
+use crate::theme::Theme;
 use chrono::{DateTime, Utc};
-use colored::Colorize;
+use futures::stream::{self, StreamExt};
 use reqwest;
 use reqwest::header::{ACCEPT, AUTHORIZATION};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
 
 const BASE_URL: &str = "https://api.starlingbank.com/api/v2";
 
+/// Most requests made to the API at once by a single call.
+const MAX_CONCURRENT_REQUESTS: usize = 4;
+
 // ACCOUNTS //////////////////////////////////////////////////////////////////////////////////////////////////
 
 /// Holds the results of the "accounts" API call
@@ -30,6 +38,23 @@ pub struct AccountDetail {
 
     #[serde(rename = "createdAt")]
     pub created_at: DateTime<Utc>,
+
+    #[serde(rename = "accountType")]
+    pub account_type: String,
+
+    pub currency: Currency,
+}
+
+// BALANCES //////////////////////////////////////////////////////////////////////////////////////////////////
+
+/// Holds the results of the "balance" API call
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Balance {
+    #[serde(rename = "clearedBalance")]
+    pub cleared: Money,
+
+    #[serde(rename = "effectiveBalance")]
+    pub effective: Money,
 }
 
 // TRANSACTIONS //////////////////////////////////////////////////////////////////////////////////////////////////
@@ -42,7 +67,7 @@ pub struct Transactions {
 }
 
 /// Represents a transaction returned from the API
-#[derive(Serialize, Deserialize, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
 pub struct Transaction {
     #[serde(rename = "transactionTime")]
     pub time: DateTime<Utc>,
@@ -60,6 +85,21 @@ pub struct Transaction {
     pub reference: String,
 
     pub status: Status,
+
+    #[serde(
+        rename = "spendingCategory",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub spending_category: Option<String>,
+
+    /// The note stored against the transaction in Starling.
+    #[serde(rename = "userNote", default, skip_serializing_if = "Option::is_none")]
+    pub user_note: Option<String>,
+
+    /// Fields returned by the API that aren't modelled above.
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Ord, PartialOrd)]
@@ -78,13 +118,54 @@ pub struct CurrencyValue {
     currency: Currency,
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum Currency {
     GBP,
     USD,
     EUR,
 }
 
+/// Represents a signed amount of money in minor units (e.g. pennies)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Money {
+    #[serde(rename = "minorUnits")]
+    pub minor_units: i64,
+    pub currency: Currency,
+}
+
+impl Money {
+    pub fn new(minor_units: i64, currency: Currency) -> Self {
+        Self {
+            minor_units,
+            currency,
+        }
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Self::new(0, currency)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.minor_units < 0 { "-" } else { "" };
+        let abs = self.minor_units.unsigned_abs();
+        write!(
+            f,
+            "{}{}.{:02} {:?}",
+            sign,
+            abs / 100,
+            abs % 100,
+            self.currency
+        )?;
+        // The alternate form `{:#}` appends the raw minor units for auditing.
+        if f.alternate() {
+            write!(f, " ({})", self.minor_units)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub enum Status {
     #[serde(rename = "UPCOMING")]
@@ -97,49 +178,127 @@ pub enum Status {
     AccountCheck,
 }
 
-impl ToString for Transaction {
-    fn to_string(&self) -> String {
-        let entry = format!(
-            "{} {} {:4}.{:0<2} {} {}",
-            format!("{}", self.time.format("%Y-%m-%d")),
-            match self.status {
-                Status::Settled => " ",
-                _ => "*",
-            },
-            self.sourceAmount.pennies / 100,
-            self.sourceAmount.pennies % 100,
-            match self.direction {
-                Direction::In => "<-",
-                Direction::Out => "->",
-            },
-            self.counterparty_name.italic(),
-        );
+impl Transaction {
+    /// An unmodelled field returned by the API, by its API name.
+    pub fn extra(&self, key: &str) -> Option<&serde_json::Value> {
+        self.extra.get(key)
+    }
+
+    /// Orders transactions by time, then by their modelled fields, e.g. for
+    /// `sorted_by(|a, b| a.sort_key().cmp(&b.sort_key()))`. `spending_category`, `user_note` and
+    /// `extra` are ignored.
+    pub fn sort_key(&self) -> impl Ord + '_ {
+        (
+            self.time,
+            &self.uid,
+            &self.counterparty_name,
+            &self.direction,
+            &self.sourceAmount,
+            &self.reference,
+            &self.status,
+        )
+    }
 
+    /// The transaction amount, positive for money in and negative for money out.
+    pub fn amount(&self) -> Money {
+        let minor_units = i64::from(self.sourceAmount.pennies);
         match self.direction {
-            Direction::In => entry.green().to_string(),
-            Direction::Out => entry.red().to_string(),
+            Direction::In => Money::new(minor_units, self.sourceAmount.currency),
+            Direction::Out => Money::new(-minor_units, self.sourceAmount.currency),
         }
     }
 }
 
+impl ToString for Transaction {
+    fn to_string(&self) -> String {
+        Theme::default().format_transaction(self)
+    }
+}
+
 // STARLING ACCOUNT //////////////////////////////////////////////////////////////////////////////////////////////////
 
+/// Settings for talking to the API
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// Limit on establishing a connection. Defaults to 10 seconds.
+    pub connect_timeout: Duration,
+    /// Limit on a whole request, from connecting to reading the body. Defaults to 30 seconds.
+    pub timeout: Duration,
+    /// Drop settled transactions whose `transactionTime` falls outside the requested window.
+    ///
+    /// Settlement timing means the API occasionally returns items just outside the window.
+    /// Off by default so those items aren't lost; turn it on when reports must cover an exact
+    /// window.
+    pub strict_window: bool,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            timeout: Duration::from_secs(30),
+            strict_window: false,
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn strict_window(mut self, strict_window: bool) -> Self {
+        self.strict_window = strict_window;
+        self
+    }
+
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Build an HTTP client with these settings.
+    pub fn build(&self) -> reqwest::Client {
+        reqwest::Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .timeout(self.timeout)
+            .build()
+            .expect("Couldn't build HTTP client")
+    }
+}
+
 /// Represents a Starling account
 #[derive(Deserialize, Debug)]
 pub struct StarlingAccount {
     pub key: ApiKey,
     pub detail: AccountDetail,
+
+    #[serde(skip)]
+    client: reqwest::Client,
+
+    #[serde(skip)]
+    config: ClientConfig,
 }
 
 impl StarlingAccount {
-    pub async fn new(key: ApiKey) -> Option<Self> {
-        let detail = Self::get_account_details(&key).await?;
-        Some(Self { key, detail })
+    pub async fn new(key: ApiKey, config: &ClientConfig) -> Option<Self> {
+        let client = config.build();
+        let detail = Self::get_account_details(&client, &key).await?;
+        Some(Self {
+            key,
+            detail,
+            client,
+            config: config.clone(),
+        })
     }
 
-    pub async fn transactions_since(&self, since: chrono::Duration) -> Vec<Transaction> {
-        let client = reqwest::Client::new();
-        let response = client
+    pub async fn transactions_since(
+        &self,
+        since: chrono::Duration,
+    ) -> Result<Vec<Transaction>, StarlingError> {
+        let response = self
+            .client
             .get(format!(
                 "{}/feed/account/{}/category/{}",
                 BASE_URL, &self.detail.account_uid, &self.detail.default_category
@@ -150,15 +309,20 @@ impl StarlingAccount {
                 changes_since: Utc::now() - since,
             })
             .send()
-            .await
-            .unwrap();
+            .await?
+            .error_for_status()?;
 
-        response.json::<Transactions>().await.unwrap().feed_items
+        Ok(response.json::<Transactions>().await?.feed_items)
     }
 
-    pub async fn settled_transactions_between(&self, since: chrono::Duration) -> Vec<Transaction> {
-        let client = reqwest::Client::new();
-        let response = client
+    pub async fn settled_transactions_between(
+        &self,
+        since: chrono::Duration,
+    ) -> Result<Vec<Transaction>, StarlingError> {
+        let max = Utc::now();
+        let min = max - since;
+        let response = self
+            .client
             .get(format!(
                 "{}/feed/account/{}/settled-transactions-between",
                 BASE_URL, &self.detail.account_uid
@@ -166,19 +330,74 @@ impl StarlingAccount {
             .header(AUTHORIZATION, format!("Bearer {}", &self.key.0))
             .header(ACCEPT, "application/json")
             .query(&QueryChangesBetween {
-                min_transaction_timestamp: Utc::now() - since,
-                max_transaction_timestamp: Utc::now(),
+                min_transaction_timestamp: min,
+                max_transaction_timestamp: max,
             })
             .send()
-            .await
-            .unwrap();
+            .await?
+            .error_for_status()?;
+
+        let mut transactions = response.json::<Transactions>().await?.feed_items;
+        if self.config.strict_window {
+            retain_window(&mut transactions, min, max);
+        }
+        Ok(transactions)
+    }
+
+    /// Get a single transaction by uid, or `None` if the account has no such transaction.
+    pub async fn transaction(&self, uid: &str) -> Result<Option<Transaction>, StarlingError> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/feed/account/{}/category/{}/{}",
+                BASE_URL, &self.detail.account_uid, &self.detail.default_category, uid
+            ))
+            .header(AUTHORIZATION, format!("Bearer {}", &self.key.0))
+            .header(ACCEPT, "application/json")
+            .send()
+            .await?;
 
-        response.json::<Transactions>().await.unwrap().feed_items
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let transaction = response.error_for_status()?.json::<Transaction>().await?;
+        Ok(Some(transaction))
+    }
+
+    /// Get several transactions by uid, at most `MAX_CONCURRENT_REQUESTS` at a time.
+    ///
+    /// A failed request only affects its own uid; the rest are still looked up.
+    pub async fn transactions_by_uids(&self, uids: &[&str]) -> UidLookup {
+        let results: Vec<_> = stream::iter(uids)
+            .map(|uid| self.transaction(uid))
+            .buffered(MAX_CONCURRENT_REQUESTS)
+            .collect()
+            .await;
+
+        UidLookup::from_results(uids, results)
+    }
+
+    pub async fn balance(&self) -> Result<Balance, StarlingError> {
+        let response = self
+            .client
+            .get(format!(
+                "{}/accounts/{}/balance",
+                BASE_URL, &self.detail.account_uid
+            ))
+            .header(AUTHORIZATION, format!("Bearer {}", &self.key.0))
+            .header(ACCEPT, "application/json")
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response.json::<Balance>().await?)
     }
 
     /// Get details for Starling account with api_key
-    async fn get_account_details(api_key: &ApiKey) -> Option<AccountDetail> {
-        let client = reqwest::Client::new();
+    async fn get_account_details(
+        client: &reqwest::Client,
+        api_key: &ApiKey,
+    ) -> Option<AccountDetail> {
         let response = match client
             .get(format!("{}/accounts", BASE_URL))
             .header(AUTHORIZATION, format!("Bearer {}", api_key.0))
@@ -187,17 +406,20 @@ impl StarlingAccount {
             .await
         {
             Ok(response) => response,
-            Err(_) => return None, // todo: this should be an error
+            Err(e) => {
+                eprintln!("ERROR: {}", StarlingError::from(e));
+                return None;
+            }
         };
 
         match response.status() {
-            reqwest::StatusCode::OK => {
-                let account_details = response
-                    .json::<AccountDetails>()
-                    .await
-                    .expect("ERROR: Couldn't serialise AccountDetails");
-                account_details.accounts.into_iter().next()
-            }
+            reqwest::StatusCode::OK => match response.json::<AccountDetails>().await {
+                Ok(account_details) => account_details.accounts.into_iter().next(),
+                Err(e) => {
+                    eprintln!("ERROR: {}", StarlingError::from(e));
+                    None
+                }
+            },
             reqwest::StatusCode::FORBIDDEN => {
                 eprintln!("ERROR: Need to grab a new token");
                 None
@@ -225,3 +447,203 @@ struct QueryChangesBetween {
     #[serde(rename = "maxTransactionTimestamp")]
     max_transaction_timestamp: DateTime<Utc>,
 }
+
+/// Outcome of looking up several transactions by uid
+#[derive(Debug, Default)]
+pub struct UidLookup {
+    /// Transactions found, in the order requested.
+    pub found: Vec<Transaction>,
+    /// Uids the account has no transaction for.
+    pub missing: Vec<String>,
+    /// Uids whose lookup failed, so whether they exist is unknown.
+    pub failed: Vec<(String, StarlingError)>,
+}
+
+impl UidLookup {
+    /// Sort each uid's lookup result into found, missing or failed.
+    fn from_results(
+        uids: &[&str],
+        results: Vec<Result<Option<Transaction>, StarlingError>>,
+    ) -> Self {
+        let mut lookup = Self::default();
+        for (uid, result) in uids.iter().zip(results) {
+            match result {
+                Ok(Some(transaction)) => lookup.found.push(transaction),
+                Ok(None) => lookup.missing.push(uid.to_string()),
+                Err(e) => lookup.failed.push((uid.to_string(), e)),
+            }
+        }
+        lookup
+    }
+}
+
+/// Keep only transactions whose time falls between `min` and `max`, inclusive.
+fn retain_window(transactions: &mut Vec<Transaction>, min: DateTime<Utc>, max: DateTime<Utc>) {
+    transactions.retain(|t| min <= t.time && t.time <= max);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::testing::transaction;
+
+    const FEED_ITEM: &str = r#"{
+        "transactionTime": "2021-12-01T10:00:00Z",
+        "feedItemUid": "uid-1",
+        "counterPartyName": "Tesco",
+        "direction": "OUT",
+        "sourceAmount": { "minorUnits": 1205, "currency": "GBP" },
+        "reference": "ref",
+        "status": "SETTLED",
+        "spendingCategory": "GROCERIES",
+        "userNote": "milk",
+        "country": "GB",
+        "roundUp": { "goalCategoryUid": "goal-1" }
+    }"#;
+
+    #[test]
+    fn unmodelled_fields_are_captured_in_extra() {
+        let t: Transaction = serde_json::from_str(FEED_ITEM).expect("Couldn't deserialise");
+        assert_eq!(t.extra("country"), Some(&serde_json::json!("GB")));
+        assert_eq!(
+            t.extra("roundUp"),
+            Some(&serde_json::json!({ "goalCategoryUid": "goal-1" }))
+        );
+        assert_eq!(t.extra.len(), 2);
+    }
+
+    #[test]
+    fn modelled_fields_do_not_leak_into_extra() {
+        let t: Transaction = serde_json::from_str(FEED_ITEM).expect("Couldn't deserialise");
+        assert_eq!(t.uid, "uid-1");
+        assert_eq!(t.spending_category.as_deref(), Some("GROCERIES"));
+        assert_eq!(t.user_note.as_deref(), Some("milk"));
+        for key in [
+            "transactionTime",
+            "feedItemUid",
+            "counterPartyName",
+            "direction",
+            "sourceAmount",
+            "reference",
+            "status",
+            "spendingCategory",
+            "userNote",
+        ] {
+            assert_eq!(t.extra(key), None, "{} leaked into extra", key);
+        }
+    }
+
+    #[test]
+    fn extra_round_trips_through_yaml() {
+        let t: Transaction = serde_json::from_str(FEED_ITEM).expect("Couldn't deserialise");
+        let yaml = serde_yaml::to_string(&t).expect("Couldn't serialise");
+        let back: Transaction = serde_yaml::from_str(&yaml).expect("Couldn't deserialise");
+        assert_eq!(back, t);
+    }
+
+    #[test]
+    fn equality_includes_extra_but_sorting_does_not() {
+        let a = transaction("uid-1", "2021-12-01T10:00:00Z", "OUT", 100, "GBP");
+        let mut b = transaction("uid-1", "2021-12-01T10:00:00Z", "OUT", 100, "GBP");
+        assert_eq!(a, b);
+
+        let country = serde_json::json!("GB");
+        b.extra.insert("country".to_string(), country);
+        assert_ne!(a, b);
+        assert_eq!(a.sort_key().cmp(&b.sort_key()), std::cmp::Ordering::Equal);
+    }
+
+    #[test]
+    fn money_display() {
+        assert_eq!(Money::new(1205, Currency::GBP).to_string(), "12.05 GBP");
+        assert_eq!(Money::new(-505, Currency::GBP).to_string(), "-5.05 GBP");
+        assert_eq!(Money::zero(Currency::EUR).to_string(), "0.00 EUR");
+    }
+
+    #[test]
+    fn money_audit_display_shows_agreeing_minor_units() {
+        assert_eq!(
+            format!("{:#}", Money::new(-505, Currency::GBP)),
+            "-5.05 GBP (-505)"
+        );
+        assert_eq!(
+            format!("{:#}", Money::new(123456, Currency::USD)),
+            "1234.56 USD (123456)"
+        );
+    }
+
+    #[test]
+    fn uid_lookup_separates_found_missing_and_failed() {
+        let uids = ["a", "b", "c", "d"];
+        let results = vec![
+            Ok(Some(transaction(
+                "a",
+                "2021-12-01T10:00:00Z",
+                "OUT",
+                1,
+                "GBP",
+            ))),
+            Ok(None),
+            Err(StarlingError::ReadTimeout),
+            Ok(Some(transaction(
+                "d",
+                "2021-12-02T10:00:00Z",
+                "OUT",
+                1,
+                "GBP",
+            ))),
+        ];
+
+        let lookup = UidLookup::from_results(&uids, results);
+
+        let found: Vec<_> = lookup.found.iter().map(|t| t.uid.as_str()).collect();
+        assert_eq!(found, vec!["a", "d"]);
+        assert_eq!(lookup.missing, vec!["b"]);
+        assert_eq!(lookup.failed.len(), 1);
+        assert_eq!(lookup.failed[0].0, "c");
+        assert!(matches!(lookup.failed[0].1, StarlingError::ReadTimeout));
+    }
+
+    #[test]
+    fn retain_window_is_inclusive_at_both_ends() {
+        let min = "2021-12-01T00:00:00Z".parse().expect("Bad min");
+        let max = "2021-12-31T00:00:00Z".parse().expect("Bad max");
+        let mut transactions = vec![
+            transaction("before", "2021-11-30T23:59:59Z", "OUT", 1, "GBP"),
+            transaction("at-min", "2021-12-01T00:00:00Z", "OUT", 1, "GBP"),
+            transaction("inside", "2021-12-15T12:00:00Z", "OUT", 1, "GBP"),
+            transaction("at-max", "2021-12-31T00:00:00Z", "OUT", 1, "GBP"),
+            transaction("after", "2021-12-31T00:00:01Z", "OUT", 1, "GBP"),
+        ];
+
+        retain_window(&mut transactions, min, max);
+
+        let uids: Vec<_> = transactions.iter().map(|t| t.uid.as_str()).collect();
+        assert_eq!(uids, vec!["at-min", "inside", "at-max"]);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::Transaction;
+
+    /// A settled transaction, built through the API representation.
+    pub fn transaction(
+        uid: &str,
+        time: &str,
+        direction: &str,
+        minor_units: u32,
+        currency: &str,
+    ) -> Transaction {
+        serde_json::from_value(serde_json::json!({
+            "transactionTime": time,
+            "feedItemUid": uid,
+            "counterPartyName": uid,
+            "direction": direction,
+            "sourceAmount": { "minorUnits": minor_units, "currency": currency },
+            "reference": "",
+            "status": "SETTLED",
+        }))
+        .expect("Couldn't build test transaction")
+    }
+}
@@ -0,0 +1,83 @@
+//! Errors from talking to the Starling API
+
+use std::fmt;
+
+/// An error from a Starling API request
+#[derive(Debug)]
+pub enum StarlingError {
+    /// The TCP/TLS connection wasn't established within the connect timeout.
+    ConnectTimeout,
+    /// The server didn't finish responding within the request timeout.
+    ReadTimeout,
+    /// Any other request, status or decoding failure.
+    Request(reqwest::Error),
+}
+
+impl fmt::Display for StarlingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StarlingError::ConnectTimeout => write!(f, "timed out connecting to Starling"),
+            StarlingError::ReadTimeout => write!(f, "timed out waiting for Starling to respond"),
+            StarlingError::Request(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StarlingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StarlingError::Request(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for StarlingError {
+    fn from(e: reqwest::Error) -> Self {
+        match (e.is_timeout(), e.is_connect()) {
+            (true, true) => StarlingError::ConnectTimeout,
+            (true, false) => StarlingError::ReadTimeout,
+            _ => StarlingError::Request(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientConfig;
+    use std::net::TcpListener;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn unanswered_request_is_read_timeout() {
+        // Accepts connections (via the backlog) but never responds.
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Couldn't bind");
+        let url = format!("http://{}/", listener.local_addr().expect("No address"));
+
+        let client = ClientConfig::default()
+            .timeout(Duration::from_millis(200))
+            .build();
+        let e = client.get(url).send().await.expect_err("Request succeeded");
+
+        assert!(matches!(StarlingError::from(e), StarlingError::ReadTimeout));
+    }
+
+    #[tokio::test]
+    async fn refused_connection_is_not_a_timeout() {
+        // Bind then drop to find a port nothing is listening on.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .expect("Couldn't bind")
+            .port();
+
+        let client = ClientConfig::default().build();
+        let e = client
+            .get(format!("http://127.0.0.1:{}/", port))
+            .send()
+            .await
+            .expect_err("Request succeeded");
+
+        assert!(matches!(StarlingError::from(e), StarlingError::Request(_)));
+    }
+}
@@ -0,0 +1,11 @@
+#![deny(unsafe_code)]
+#![warn(clippy::unwrap_used)]
+
+pub mod cli;
+pub mod client;
+pub mod error;
+pub mod persist;
+pub mod prelude;
+pub mod report;
+pub mod summary;
+pub mod theme;
@@ -1,22 +1,42 @@
 #![deny(unsafe_code)]
 #![warn(clippy::unwrap_used)]
 
-mod cli;
-mod client;
-mod persist;
-use crate::client::StarlingAccount;
 use clap::Parser;
 use futures::future::join_all;
+use test_starling::client::StarlingAccount;
+use test_starling::theme::Theme;
+use test_starling::{cli, persist};
 
 #[tokio::main]
 async fn main() {
+    let args = cli::Args::parse();
+    if args.no_color {
+        colored::control::set_override(false);
+    }
+
+    let tokens_file = args.config_dir.join(persist::TOKENS_FILE);
+
+    // Profile management doesn't need the API.
+    if let cli::Command::RenameProfile { from, to } = &args.command {
+        return cli::do_rename_profile(&tokens_file, from, to);
+    }
+
+    let theme = match Theme::load(args.theme, &args.config_dir) {
+        Ok(theme) => theme,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     // Get accounts for each token.
-    let tokens = persist::Tokens::new("tokens.yml");
+    let tokens = persist::Tokens::new(&tokens_file);
+    let config = args.client_config();
 
     let accounts = join_all(
         tokens
             .into_iter()
-            .map(|t| StarlingAccount::new(t.key))
+            .map(|t| StarlingAccount::new(t.key, &config))
             .collect::<Vec<_>>(),
     )
     .await
@@ -24,9 +44,46 @@ async fn main() {
     .flatten()
     .collect::<Vec<_>>();
 
-    let args = cli::Args::parse();
     match args.command {
-        cli::Command::Balances => todo!(),
-        cli::Command::Update { days } => cli::do_update(&accounts, days).await,
+        cli::Command::Balances => cli::do_balances(&accounts, args.json, args.audit, &theme).await,
+        cli::Command::Update { days, seed_notes } => {
+            cli::do_update(
+                &accounts,
+                days,
+                seed_notes,
+                args.audit,
+                args.json,
+                &args.data_dir,
+                &theme,
+            )
+            .await
+        }
+        cli::Command::Weekly { days, week_start } => {
+            cli::do_weekly(&accounts, days, week_start, args.audit, args.json, &theme).await
+        }
+        cli::Command::Merchants {
+            days,
+            aggregate_below,
+        } => cli::do_merchants(&accounts, days, aggregate_below, args.audit, args.json).await,
+        cli::Command::Categories {
+            days,
+            chart,
+            aggregate_below,
+        } => {
+            cli::do_categories(
+                &accounts,
+                days,
+                chart,
+                aggregate_below,
+                args.audit,
+                args.json,
+            )
+            .await
+        }
+        cli::Command::Tail { n } => cli::do_tail(&accounts, n, args.json, &theme).await,
+        cli::Command::GetMany { uids } => {
+            cli::do_get_many(&accounts, &uids, args.json, &theme).await
+        }
+        cli::Command::RenameProfile { .. } => unreachable!(),
     }
 }
@@ -7,8 +7,11 @@ use std::thread::current;
 use crate::client::{Transaction, Transactions};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::Path;
 
+pub const TOKENS_FILE: &str = "tokens.yml";
 const TRANSACTION_FILE: &str = "transactions.yml";
+const NOTES_FILE: &str = "notes.yml";
 
 /// Starling API auth tokens for each account.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -26,10 +29,35 @@ pub struct Tokens(pub Vec<Token>);
 
 /// Render tokens as an array.
 impl Tokens {
-    pub fn new(filename: &str) -> Self {
-        let f = std::fs::File::open(String::from(filename)).expect("Couldn't open tokens_file");
+    pub fn new(filename: &Path) -> Self {
+        let f = std::fs::File::open(filename).expect("Couldn't open tokens_file");
         serde_yaml::from_reader(f).expect("Could not deserialise yaml")
     }
+
+    /// Write tokens back to the file system.
+    pub fn save(&self, filename: &Path) {
+        let f = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(filename)
+            .expect("Couldn't open tokens_file");
+        serde_yaml::to_writer(f, self).expect("Could not serialise yaml");
+    }
+
+    /// Rename the profile `from` to `to`, keeping its key.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<(), String> {
+        if self.0.iter().any(|t| t.name == to) {
+            return Err(format!("profile '{}' already exists", to));
+        }
+        let token = self
+            .0
+            .iter_mut()
+            .find(|t| t.name == from)
+            .ok_or_else(|| format!("no profile named '{}'", from))?;
+        token.name = to.to_string();
+        Ok(())
+    }
 }
 
 impl IntoIterator for Tokens {
@@ -41,29 +69,216 @@ impl IntoIterator for Tokens {
     }
 }
 
-/// Write transactions to the file system.
-pub fn update_transactions(new_transactions: Vec<Transaction>) {
-    println!("Writing transactions to file system");
+/// Local notes, keyed by transaction uid.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Notes(pub HashMap<String, String>);
+
+impl Notes {
+    /// Load notes from `data_dir`, or none if there aren't any yet.
+    pub fn load(data_dir: &Path) -> Self {
+        match std::fs::File::open(data_dir.join(NOTES_FILE)) {
+            Ok(f) => serde_yaml::from_reader(f).expect("Could not deserialise yaml"),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Write notes to `data_dir`.
+    pub fn save(&self, data_dir: &Path) {
+        std::fs::create_dir_all(data_dir).expect("Couldn't create data directory");
+        let f = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(data_dir.join(NOTES_FILE))
+            .expect("Couldn't open file");
+        serde_yaml::to_writer(f, self).expect("Could not serialise yaml");
+    }
+
+    /// Copy Starling's `userNote` into local notes for transactions without one.
+    ///
+    /// This only ever adds notes: existing local notes are never overwritten, and nothing is
+    /// written back to Starling. Returns the number of notes added.
+    pub fn seed(&mut self, transactions: &[Transaction]) -> usize {
+        let mut seeded = 0;
+        for t in transactions {
+            if let Some(note) = t.user_note.as_ref().filter(|n| !n.is_empty()) {
+                if !self.0.contains_key(&t.uid) {
+                    self.0.insert(t.uid.clone(), note.clone());
+                    seeded += 1;
+                }
+            }
+        }
+        seeded
+    }
+}
+
+/// Write transactions to the file system under `data_dir`.
+pub fn update_transactions(new_transactions: Vec<Transaction>, data_dir: &Path) {
+    let path = data_dir.join(TRANSACTION_FILE);
 
     // Load current transactions if they exist
-    let f = std::fs::File::open(String::from(TRANSACTION_FILE)).expect("Fail");
-    let mut current_transactions: HashMap<String, Transaction> = serde_yaml::from_reader(f).unwrap();
+    let mut current_transactions: HashMap<String, Transaction> = match std::fs::File::open(&path) {
+        Ok(f) => serde_yaml::from_reader(f).expect("Could not deserialise yaml"),
+        Err(_) => HashMap::new(),
+    };
 
     // Add new transactions
     for nt in new_transactions.into_iter() {
-        current_transactions.insert(nt.uid, nt);
+        current_transactions.insert(nt.uid.clone(), nt);
     }
 
     // Save updated transactions
+    std::fs::create_dir_all(data_dir).expect("Couldn't create data directory");
     let f = std::fs::OpenOptions::new()
         .write(true)
         .create(true)
         .truncate(true)
-        .open(TRANSACTION_FILE)
+        .open(&path)
         .expect("Couldn't open file");
-    serde_yaml::to_writer(f, &current_transactions).unwrap();
+    serde_yaml::to_writer(f, &current_transactions).expect("Could not serialise yaml");
 }
 
 fn does_exist_in(t: &Transaction, transactions: &Vec<Transaction>) -> bool {
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::testing::transaction;
+    use std::path::PathBuf;
+
+    /// An empty directory for this test, unique to the test process.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("test_starling-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn tokens() -> Tokens {
+        Tokens(vec![
+            Token {
+                key: ApiKey("key-new".to_string()),
+                name: "new".to_string(),
+            },
+            Token {
+                key: ApiKey("key-personal".to_string()),
+                name: "personal".to_string(),
+            },
+        ])
+    }
+
+    fn key_for<'a>(tokens: &'a Tokens, name: &str) -> Option<&'a str> {
+        tokens
+            .0
+            .iter()
+            .find(|t| t.name == name)
+            .map(|t| t.key.0.as_str())
+    }
+
+    #[test]
+    fn rename_profile_keeps_key() {
+        let mut tokens = tokens();
+        assert_eq!(tokens.rename("new", "business"), Ok(()));
+        assert_eq!(key_for(&tokens, "business"), Some("key-new"));
+        assert_eq!(key_for(&tokens, "new"), None);
+        assert_eq!(key_for(&tokens, "personal"), Some("key-personal"));
+    }
+
+    #[test]
+    fn rename_profile_to_existing_name_fails() {
+        let mut tokens = tokens();
+        assert!(tokens.rename("new", "personal").is_err());
+        assert_eq!(key_for(&tokens, "new"), Some("key-new"));
+        assert_eq!(key_for(&tokens, "personal"), Some("key-personal"));
+    }
+
+    #[test]
+    fn rename_missing_profile_fails() {
+        let mut tokens = tokens();
+        assert!(tokens.rename("missing", "business").is_err());
+        assert_eq!(key_for(&tokens, "business"), None);
+    }
+
+    #[test]
+    fn renamed_profile_is_saved() {
+        let path = temp_dir("tokens").join(TOKENS_FILE);
+        std::fs::create_dir_all(path.parent().expect("No parent")).expect("Couldn't create dir");
+        let mut tokens = tokens();
+        tokens.rename("new", "business").expect("Rename failed");
+        tokens.save(&path);
+
+        assert_eq!(key_for(&Tokens::new(&path), "business"), Some("key-new"));
+    }
+
+    fn noted(uid: &str, note: Option<&str>) -> Transaction {
+        let mut t = transaction(uid, "2021-12-01T10:00:00Z", "OUT", 100, "GBP");
+        t.user_note = note.map(str::to_string);
+        t
+    }
+
+    #[test]
+    fn seed_fills_only_empty_local_notes() {
+        let mut notes = Notes::default();
+        notes.0.insert("kept".to_string(), "local".to_string());
+
+        let seeded = notes.seed(&[
+            noted("kept", Some("from starling")),
+            noted("filled", Some("from starling")),
+            noted("no-note", None),
+        ]);
+
+        assert_eq!(seeded, 1);
+        assert_eq!(notes.0["kept"], "local");
+        assert_eq!(notes.0["filled"], "from starling");
+        assert!(!notes.0.contains_key("no-note"));
+    }
+
+    #[test]
+    fn seed_ignores_empty_user_note() {
+        let mut notes = Notes::default();
+        assert_eq!(notes.seed(&[noted("blank", Some(""))]), 0);
+        assert!(notes.0.is_empty());
+    }
+
+    #[test]
+    fn seeded_notes_round_trip() {
+        let data_dir = temp_dir("notes");
+        let mut notes = Notes::load(&data_dir);
+        notes.seed(&[noted("uid-1", Some("milk"))]);
+        notes.save(&data_dir);
+
+        assert_eq!(Notes::load(&data_dir).0["uid-1"], "milk");
+    }
+
+    #[test]
+    fn update_transactions_writes_to_data_dir() {
+        let data_dir = temp_dir("data-dir").join("nested");
+        let t = transaction("uid-1", "2021-12-01T10:00:00Z", "OUT", 100, "GBP");
+
+        update_transactions(vec![t], &data_dir);
+
+        let path = data_dir.join(TRANSACTION_FILE);
+        assert!(path.exists());
+        let stored: HashMap<String, Transaction> =
+            serde_yaml::from_reader(std::fs::File::open(path).expect("Couldn't open file"))
+                .expect("Could not deserialise yaml");
+        assert!(stored.contains_key("uid-1"));
+    }
+
+    #[test]
+    fn update_transactions_merges_with_existing() {
+        let data_dir = temp_dir("merge");
+        let t = |uid| transaction(uid, "2021-12-01T10:00:00Z", "OUT", 100, "GBP");
+
+        update_transactions(vec![t("uid-1")], &data_dir);
+        update_transactions(vec![t("uid-2")], &data_dir);
+
+        let stored: HashMap<String, Transaction> = serde_yaml::from_reader(
+            std::fs::File::open(data_dir.join(TRANSACTION_FILE)).expect("Couldn't open file"),
+        )
+        .expect("Could not deserialise yaml");
+        assert_eq!(stored.len(), 2);
+    }
+}
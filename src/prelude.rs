@@ -0,0 +1,8 @@
+//! Commonly needed types, for `use test_starling::prelude::*`
+
+pub use crate::client::{
+    Balance, ClientConfig, Currency, Direction, Money, StarlingAccount, Status, Transaction,
+    UidLookup,
+};
+pub use crate::error::StarlingError;
+pub use crate::summary::{summarize, Summary};
@@ -0,0 +1,395 @@
+//! Report aggregation
+
+use crate::client::{Currency, Direction, Money, Transaction};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use clap::ArgEnum;
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// The day a reporting week begins on
+#[derive(ArgEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WeekStart {
+    /// ISO weeks
+    Monday,
+    /// US convention
+    Sunday,
+}
+
+/// The first day of the week containing `date`.
+///
+/// Weeks are labelled by this date rather than a week number, so a week spanning a year
+/// boundary is unambiguous under either convention.
+pub fn week_beginning(date: NaiveDate, week_start: WeekStart) -> NaiveDate {
+    let offset = match week_start {
+        WeekStart::Monday => date.weekday().num_days_from_monday(),
+        WeekStart::Sunday => date.weekday().num_days_from_sunday(),
+    };
+    date - Duration::days(i64::from(offset))
+}
+
+/// The start of a lookback of `days` from `now`, moved back to midnight at the beginning of its
+/// week so the first bucket is a whole week.
+pub fn week_aligned_start(now: DateTime<Utc>, days: i64, week_start: WeekStart) -> DateTime<Utc> {
+    let first_day = week_beginning((now - Duration::days(days)).date().naive_utc(), week_start);
+    DateTime::from_utc(first_day.and_hms(0, 0, 0), Utc)
+}
+
+/// Bucket transactions by the week they fall in, keyed by the first day of that week.
+pub fn by_week(
+    transactions: Vec<Transaction>,
+    week_start: WeekStart,
+) -> BTreeMap<NaiveDate, Vec<Transaction>> {
+    let mut weeks: BTreeMap<NaiveDate, Vec<Transaction>> = BTreeMap::new();
+    for t in transactions {
+        let week = week_beginning(t.time.date().naive_utc(), week_start);
+        weeks.entry(week).or_default().push(t);
+    }
+    weeks
+}
+
+/// Total spend with one merchant or category in one currency
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SpendTotal {
+    pub name: String,
+    /// Money out, as a positive amount.
+    pub total: Money,
+    /// Number of transactions making up the total.
+    pub count: usize,
+}
+
+/// Total money out per merchant, largest first.
+pub fn merchant_totals(transactions: &[Transaction]) -> Vec<SpendTotal> {
+    out_totals_by(transactions, |t| t.counterparty_name.as_str())
+}
+
+/// Total money out per spending category, largest first.
+pub fn category_totals(transactions: &[Transaction]) -> Vec<SpendTotal> {
+    out_totals_by(transactions, |t| {
+        t.spending_category.as_deref().unwrap_or(UNCATEGORISED)
+    })
+}
+
+/// Label for transactions without a spending category.
+const UNCATEGORISED: &str = "UNCATEGORISED";
+
+/// Total money out grouped by `key`, largest first.
+fn out_totals_by<'a, F>(transactions: &'a [Transaction], key: F) -> Vec<SpendTotal>
+where
+    F: Fn(&'a Transaction) -> &'a str,
+{
+    let mut totals: HashMap<(&str, Currency), SpendTotal> = HashMap::new();
+    for t in transactions
+        .iter()
+        .filter(|t| t.direction == Direction::Out)
+    {
+        let amount = t.amount();
+        let name = key(t);
+        let total = totals
+            .entry((name, amount.currency))
+            .or_insert_with(|| SpendTotal {
+                name: name.to_string(),
+                total: Money::zero(amount.currency),
+                count: 0,
+            });
+        total.total.minor_units -= amount.minor_units;
+        total.count += 1;
+    }
+
+    totals
+        .into_values()
+        .sorted_by(|a, b| {
+            b.total
+                .minor_units
+                .cmp(&a.total.minor_units)
+                .then_with(|| a.name.cmp(&b.name))
+        })
+        .collect()
+}
+
+/// The sum of `totals` in each currency.
+pub fn grand_totals(totals: &[SpendTotal]) -> Vec<Money> {
+    totals
+        .iter()
+        .into_group_map_by(|m| m.total.currency)
+        .into_iter()
+        .sorted_by_key(|(currency, _)| *currency)
+        .map(|(currency, totals)| {
+            Money::new(totals.iter().map(|m| m.total.minor_units).sum(), currency)
+        })
+        .collect()
+}
+
+/// Roll totals below `threshold` minor units into one "Other" row per currency, placed last and
+/// labelled with how many were rolled up, e.g. "Other (3 merchants)".
+///
+/// `noun` names what the totals are, singular and plural, e.g. `("merchant", "merchants")`.
+/// The threshold applies to each merchant's or category's total, not to individual
+/// transactions, so the grand total is unchanged.
+pub fn aggregate_below(
+    totals: Vec<SpendTotal>,
+    threshold: i64,
+    noun: (&str, &str),
+) -> Vec<SpendTotal> {
+    let (mut kept, small): (Vec<_>, Vec<_>) = totals
+        .into_iter()
+        .partition(|m| m.total.minor_units >= threshold);
+
+    let others = small
+        .into_iter()
+        .into_group_map_by(|m| m.total.currency)
+        .into_iter()
+        .sorted_by_key(|(currency, _)| *currency)
+        .map(|(currency, merchants)| SpendTotal {
+            name: match merchants.len() {
+                1 => format!("Other (1 {})", noun.0),
+                n => format!("Other ({} {})", n, noun.1),
+            },
+            total: Money::new(
+                merchants.iter().map(|m| m.total.minor_units).sum(),
+                currency,
+            ),
+            count: merchants.iter().map(|m| m.count).sum(),
+        });
+
+    kept.extend(others);
+    kept
+}
+
+/// Parse a non-negative decimal amount such as `5`, `5.5` or `5.00` into minor units.
+pub fn parse_amount(s: &str) -> Result<i64, String> {
+    let invalid = || format!("invalid amount '{}'", s);
+    let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+    let digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+    if whole.is_empty() || !digits(whole) || fraction.len() > 2 || !digits(fraction) {
+        return Err(invalid());
+    }
+    let whole: i64 = whole.parse().map_err(|_| invalid())?;
+    let fraction: i64 = format!("{:0<2}", fraction).parse().map_err(|_| invalid())?;
+    whole
+        .checked_mul(100)
+        .and_then(|pennies| pennies.checked_add(fraction))
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::testing::transaction;
+
+    fn date(s: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").expect("Bad test date")
+    }
+
+    /// Thursday 2021-12-30 to Monday 2022-01-03, across the year boundary.
+    fn year_end() -> Vec<Transaction> {
+        [
+            "2021-12-30",
+            "2021-12-31",
+            "2022-01-01",
+            "2022-01-02",
+            "2022-01-03",
+        ]
+        .iter()
+        .map(|d| transaction(d, &format!("{}T12:00:00Z", d), "OUT", 100, "GBP"))
+        .collect()
+    }
+
+    fn bucket_uids(weeks: &BTreeMap<NaiveDate, Vec<Transaction>>) -> Vec<(NaiveDate, Vec<&str>)> {
+        weeks
+            .iter()
+            .map(|(week, ts)| (*week, ts.iter().map(|t| t.uid.as_str()).collect()))
+            .collect()
+    }
+
+    fn spend(uid: &str, merchant: &str, direction: &str, minor_units: u32) -> Transaction {
+        let currency = if merchant == "Bakery" { "EUR" } else { "GBP" };
+        let mut t = transaction(
+            uid,
+            "2021-12-01T10:00:00Z",
+            direction,
+            minor_units,
+            currency,
+        );
+        t.counterparty_name = merchant.to_string();
+        t
+    }
+
+    /// Tesco is big, Kiosk and Parking are small, and Bakery is small but in EUR. The refund
+    /// from Tesco is money in, so it isn't spend.
+    fn merchants() -> Vec<SpendTotal> {
+        merchant_totals(&[
+            spend("a", "Tesco", "OUT", 3000),
+            spend("b", "Tesco", "OUT", 2500),
+            spend("c", "Kiosk", "OUT", 120),
+            spend("d", "Parking", "OUT", 300),
+            spend("e", "Bakery", "OUT", 450),
+            spend("f", "Tesco", "IN", 9999),
+        ])
+    }
+
+    #[test]
+    fn merchant_totals_sum_money_out_largest_first() {
+        let totals = merchants();
+        let rows: Vec<_> = totals
+            .iter()
+            .map(|t| (t.name.as_str(), t.total, t.count))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("Tesco", Money::new(5500, Currency::GBP), 2),
+                ("Bakery", Money::new(450, Currency::EUR), 1),
+                ("Parking", Money::new(300, Currency::GBP), 1),
+                ("Kiosk", Money::new(120, Currency::GBP), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn small_merchants_collapse_into_other() {
+        let totals = merchants();
+        let before = grand_totals(&totals);
+
+        let aggregated = aggregate_below(totals, 500, ("merchant", "merchants"));
+        let rows: Vec<_> = aggregated
+            .iter()
+            .map(|t| (t.name.as_str(), t.total, t.count))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("Tesco", Money::new(5500, Currency::GBP), 2),
+                ("Other (2 merchants)", Money::new(420, Currency::GBP), 2),
+                ("Other (1 merchant)", Money::new(450, Currency::EUR), 1),
+            ]
+        );
+        assert_eq!(grand_totals(&aggregated), before);
+    }
+
+    #[test]
+    fn nothing_below_threshold_is_unchanged() {
+        let totals = merchants();
+        assert_eq!(
+            aggregate_below(totals.clone(), 0, ("merchant", "merchants")),
+            totals
+        );
+    }
+
+    #[test]
+    fn small_categories_collapse_into_other() {
+        let mut transactions = vec![
+            spend("a", "Tesco", "OUT", 3000),
+            spend("b", "Cafe", "OUT", 200),
+            spend("c", "Bus", "OUT", 150),
+        ];
+        for (t, category) in transactions.iter_mut().zip(["GROCERIES", "EATING_OUT"]) {
+            t.spending_category = Some(category.to_string());
+        }
+        let totals = category_totals(&transactions);
+        let before = grand_totals(&totals);
+
+        let aggregated = aggregate_below(totals, 500, ("category", "categories"));
+        let names: Vec<_> = aggregated.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, vec!["GROCERIES", "Other (2 categories)"]);
+        assert_eq!(grand_totals(&aggregated), before);
+    }
+
+    #[test]
+    fn parse_amount_cases() {
+        assert_eq!(parse_amount("5"), Ok(500));
+        assert_eq!(parse_amount("5.5"), Ok(550));
+        assert_eq!(parse_amount("5.05"), Ok(505));
+        assert_eq!(parse_amount("0.5"), Ok(50));
+        assert!(parse_amount(".5").is_err());
+        assert!(parse_amount("5.123").is_err());
+        assert!(parse_amount("-1").is_err());
+        assert!(parse_amount("x").is_err());
+        assert!(parse_amount("999999999999999999").is_err());
+    }
+
+    #[test]
+    fn week_beginning_under_both_conventions() {
+        // 2022-01-01 is a Saturday.
+        assert_eq!(
+            week_beginning(date("2022-01-01"), WeekStart::Monday),
+            date("2021-12-27")
+        );
+        assert_eq!(
+            week_beginning(date("2022-01-01"), WeekStart::Sunday),
+            date("2021-12-26")
+        );
+        // A week start is its own week beginning.
+        assert_eq!(
+            week_beginning(date("2022-01-03"), WeekStart::Monday),
+            date("2022-01-03")
+        );
+        assert_eq!(
+            week_beginning(date("2022-01-02"), WeekStart::Sunday),
+            date("2022-01-02")
+        );
+    }
+
+    #[test]
+    fn monday_weeks_across_year_boundary() {
+        let weeks = by_week(year_end(), WeekStart::Monday);
+        assert_eq!(
+            bucket_uids(&weeks),
+            vec![
+                (
+                    date("2021-12-27"),
+                    vec!["2021-12-30", "2021-12-31", "2022-01-01", "2022-01-02"]
+                ),
+                (date("2022-01-03"), vec!["2022-01-03"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn sunday_weeks_across_year_boundary() {
+        let weeks = by_week(year_end(), WeekStart::Sunday);
+        assert_eq!(
+            bucket_uids(&weeks),
+            vec![
+                (
+                    date("2021-12-26"),
+                    vec!["2021-12-30", "2021-12-31", "2022-01-01"]
+                ),
+                (date("2022-01-02"), vec!["2022-01-02", "2022-01-03"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn lookback_is_aligned_to_week_start() {
+        // Wednesday afternoon, looking back 28 days to Wednesday 2021-12-08.
+        let now = DateTime::parse_from_rfc3339("2022-01-05T15:30:00Z")
+            .expect("Bad test time")
+            .with_timezone(&Utc);
+        assert_eq!(
+            week_aligned_start(now, 28, WeekStart::Monday).to_rfc3339(),
+            "2021-12-06T00:00:00+00:00"
+        );
+        assert_eq!(
+            week_aligned_start(now, 28, WeekStart::Sunday).to_rfc3339(),
+            "2021-12-05T00:00:00+00:00"
+        );
+    }
+
+    #[test]
+    fn spend_total_json() {
+        let total = SpendTotal {
+            name: "Tesco".to_string(),
+            total: Money::new(1250, Currency::GBP),
+            count: 2,
+        };
+        assert_eq!(
+            serde_json::to_value(&total).expect("Couldn't serialise total"),
+            serde_json::json!({
+                "name": "Tesco",
+                "total": { "minorUnits": 1250, "currency": "GBP" },
+                "count": 2,
+            })
+        );
+    }
+}
@@ -0,0 +1,186 @@
+//! Totals over a set of transactions
+
+use crate::client::{Currency, Direction, Money, Transaction};
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Totals for a set of transactions.
+///
+/// `total_in`, `total_out` and `net` are only given when every transaction shares a single
+/// currency. Mixed-currency sets have no meaningful single total, so they are `None` and the
+/// figures must be read from `per_currency` instead.
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Summary {
+    /// Sum of money in.
+    pub total_in: Option<Money>,
+    /// Sum of money out, as a positive amount.
+    pub total_out: Option<Money>,
+    /// Money in less money out.
+    pub net: Option<Money>,
+    /// Number of transactions summarised.
+    pub count: usize,
+    /// `(in, out)` totals for each currency seen.
+    pub per_currency: HashMap<Currency, (Money, Money)>,
+}
+
+impl Summary {
+    /// True if the transactions span more than one currency.
+    pub fn is_mixed_currency(&self) -> bool {
+        self.per_currency.len() > 1
+    }
+}
+
+/// Summarise a set of transactions.
+pub fn summarize(txns: &[Transaction]) -> Summary {
+    let mut per_currency: HashMap<Currency, (Money, Money)> = HashMap::new();
+
+    for t in txns {
+        let amount = t.amount();
+        let (total_in, total_out) = per_currency
+            .entry(amount.currency)
+            .or_insert_with(|| (Money::zero(amount.currency), Money::zero(amount.currency)));
+        match t.direction {
+            Direction::In => total_in.minor_units += amount.minor_units,
+            Direction::Out => total_out.minor_units -= amount.minor_units,
+        }
+    }
+
+    let single = if per_currency.len() == 1 {
+        per_currency.values().next().copied()
+    } else {
+        None
+    };
+
+    Summary {
+        total_in: single.map(|(i, _)| i),
+        total_out: single.map(|(_, o)| o),
+        net: single.map(|(i, o)| Money::new(i.minor_units - o.minor_units, i.currency)),
+        count: txns.len(),
+        per_currency,
+    }
+}
+
+/// The alternate form `{:#}` shows each total's raw minor units alongside it.
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.count {
+            1 => write!(f, "1 transaction")?,
+            n => write!(f, "{} transactions", n)?,
+        }
+        for (currency, (total_in, total_out)) in
+            self.per_currency.iter().sorted_by_key(|(c, _)| **c)
+        {
+            let net = Money::new(total_in.minor_units - total_out.minor_units, *currency);
+            if f.alternate() {
+                write!(f, "\n  in {:#} out {:#} net {:#}", total_in, total_out, net)?;
+            } else {
+                write!(f, "\n  in {} out {} net {}", total_in, total_out, net)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::testing::transaction;
+
+    const TIME: &str = "2021-12-01T10:00:00Z";
+
+    #[test]
+    fn empty() {
+        let summary = summarize(&[]);
+        assert_eq!(summary, Summary::default());
+        assert_eq!(summary.to_string(), "0 transactions");
+    }
+
+    #[test]
+    fn in_only() {
+        let summary = summarize(&[
+            transaction("a", TIME, "IN", 1000, "GBP"),
+            transaction("b", TIME, "IN", 250, "GBP"),
+        ]);
+        assert_eq!(summary.total_in, Some(Money::new(1250, Currency::GBP)));
+        assert_eq!(summary.total_out, Some(Money::zero(Currency::GBP)));
+        assert_eq!(summary.net, Some(Money::new(1250, Currency::GBP)));
+        assert_eq!(summary.count, 2);
+    }
+
+    #[test]
+    fn out_only() {
+        let summary = summarize(&[transaction("a", TIME, "OUT", 505, "GBP")]);
+        assert_eq!(summary.total_in, Some(Money::zero(Currency::GBP)));
+        assert_eq!(summary.total_out, Some(Money::new(505, Currency::GBP)));
+        assert_eq!(summary.net, Some(Money::new(-505, Currency::GBP)));
+        assert!(summary.to_string().starts_with("1 transaction\n"));
+    }
+
+    #[test]
+    fn net_sign_follows_larger_side() {
+        let summary = summarize(&[
+            transaction("a", TIME, "IN", 1000, "GBP"),
+            transaction("b", TIME, "OUT", 1500, "GBP"),
+        ]);
+        assert_eq!(summary.net, Some(Money::new(-500, Currency::GBP)));
+
+        let summary = summarize(&[
+            transaction("a", TIME, "IN", 1500, "GBP"),
+            transaction("b", TIME, "OUT", 1000, "GBP"),
+        ]);
+        assert_eq!(summary.net, Some(Money::new(500, Currency::GBP)));
+        assert!(!summary.is_mixed_currency());
+    }
+
+    #[test]
+    fn mixed_currency_has_no_single_total() {
+        let summary = summarize(&[
+            transaction("a", TIME, "IN", 1000, "GBP"),
+            transaction("b", TIME, "OUT", 300, "GBP"),
+            transaction("c", TIME, "OUT", 700, "EUR"),
+        ]);
+        assert!(summary.is_mixed_currency());
+        assert_eq!(summary.total_in, None);
+        assert_eq!(summary.total_out, None);
+        assert_eq!(summary.net, None);
+        assert_eq!(summary.count, 3);
+        assert_eq!(
+            summary.per_currency[&Currency::GBP],
+            (
+                Money::new(1000, Currency::GBP),
+                Money::new(300, Currency::GBP)
+            )
+        );
+        assert_eq!(
+            summary.per_currency[&Currency::EUR],
+            (Money::zero(Currency::EUR), Money::new(700, Currency::EUR))
+        );
+    }
+
+    #[test]
+    fn audit_form_adds_minor_units_without_changing_totals() {
+        let summary = summarize(&[
+            transaction("a", TIME, "IN", 1000, "GBP"),
+            transaction("b", TIME, "OUT", 1505, "GBP"),
+        ]);
+        assert_eq!(
+            summary.to_string(),
+            "2 transactions\n  in 10.00 GBP out 15.05 GBP net -5.05 GBP"
+        );
+        assert_eq!(
+            format!("{:#}", summary),
+            "2 transactions\n  in 10.00 GBP (1000) out 15.05 GBP (1505) net -5.05 GBP (-505)"
+        );
+    }
+
+    #[test]
+    fn serialises_to_json() {
+        let summary = summarize(&[transaction("a", TIME, "OUT", 505, "GBP")]);
+        let json = serde_json::to_value(&summary).expect("Couldn't serialise summary");
+        assert_eq!(json["count"], 1);
+        assert_eq!(json["net"]["minorUnits"], -505);
+        assert_eq!(json["per_currency"]["GBP"][1]["minorUnits"], 505);
+    }
+}
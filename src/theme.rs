@@ -0,0 +1,287 @@
+//! Output colour themes
+
+use crate::client::{Direction, Status, Transaction};
+use clap::ArgEnum;
+use colored::{Color, Colorize};
+use serde::Deserialize;
+use std::path::Path;
+
+/// Optional colour overrides, in the config directory.
+pub const THEME_FILE: &str = "theme.yml";
+
+/// Colours used by the CLI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// Money in.
+    pub incoming: Color,
+    /// Money out.
+    pub outgoing: Color,
+    /// The marker on transactions that haven't settled.
+    pub pending: Color,
+    /// Table and section headers, which are bold in the terminal's own colour if `None`.
+    pub header: Option<Color>,
+}
+
+/// Themes selectable with `--theme`
+#[derive(ArgEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BuiltinTheme {
+    /// Green in, red out
+    Default,
+    /// Blue in, orange out, from the Okabe-Ito palette
+    Colourblind,
+}
+
+impl BuiltinTheme {
+    pub fn theme(self) -> Theme {
+        match self {
+            BuiltinTheme::Default => Theme {
+                incoming: Color::Green,
+                outgoing: Color::Red,
+                pending: Color::Yellow,
+                header: None,
+            },
+            BuiltinTheme::Colourblind => Theme {
+                incoming: Color::TrueColor {
+                    r: 0,
+                    g: 114,
+                    b: 178,
+                },
+                outgoing: Color::TrueColor {
+                    r: 230,
+                    g: 159,
+                    b: 0,
+                },
+                pending: Color::TrueColor {
+                    r: 240,
+                    g: 228,
+                    b: 66,
+                },
+                header: None,
+            },
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        BuiltinTheme::Default.theme()
+    }
+}
+
+/// Colours set in the theme file, each a name such as `blue` or a hex value such as `#0072b2`
+#[derive(Deserialize, Debug, Default)]
+struct ThemeOverrides {
+    #[serde(rename = "in")]
+    incoming: Option<String>,
+    #[serde(rename = "out")]
+    outgoing: Option<String>,
+    pending: Option<String>,
+    header: Option<String>,
+}
+
+impl Theme {
+    /// The `builtin` theme, with any colours set in the config directory's theme file applied
+    /// on top.
+    pub fn load(builtin: BuiltinTheme, config_dir: &Path) -> Result<Self, String> {
+        let mut theme = builtin.theme();
+        let overrides: ThemeOverrides = match std::fs::File::open(config_dir.join(THEME_FILE)) {
+            Ok(f) => serde_yaml::from_reader(f).map_err(|e| format!("{}: {}", THEME_FILE, e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(theme),
+            Err(e) => return Err(format!("{}: {}", THEME_FILE, e)),
+        };
+
+        for (color, value) in [
+            (&mut theme.incoming, overrides.incoming),
+            (&mut theme.outgoing, overrides.outgoing),
+            (&mut theme.pending, overrides.pending),
+        ] {
+            if let Some(value) = value {
+                *color = parse_color(&value)?;
+            }
+        }
+        if let Some(value) = overrides.header {
+            theme.header = Some(parse_color(&value)?);
+        }
+        Ok(theme)
+    }
+
+    /// Render a transaction as a single coloured line.
+    pub fn format_transaction(&self, t: &Transaction) -> String {
+        let color = match t.direction {
+            Direction::In => self.incoming,
+            Direction::Out => self.outgoing,
+        };
+        let marker = match t.status {
+            Status::Settled => " ".normal(),
+            _ => "*".color(self.pending),
+        };
+        let pennies = t.amount().minor_units.unsigned_abs();
+        let amount = format!(
+            "{:4}.{:02} {}",
+            pennies / 100,
+            pennies % 100,
+            match t.direction {
+                Direction::In => "<-",
+                Direction::Out => "->",
+            },
+        );
+
+        format!(
+            "{} {} {} {}",
+            t.time.format("%Y-%m-%d").to_string().color(color),
+            marker,
+            amount.color(color),
+            t.counterparty_name.italic().color(color),
+        )
+    }
+
+    /// Render a header.
+    pub fn header(&self, text: &str) -> String {
+        match self.header {
+            Some(color) => text.color(color).bold().to_string(),
+            None => text.bold().to_string(),
+        }
+    }
+}
+
+/// Parse a colour name such as `bright blue`, or a hex value such as `#0072b2`.
+pub fn parse_color(s: &str) -> Result<Color, String> {
+    let invalid = || format!("invalid colour '{}'", s);
+    match s.strip_prefix('#') {
+        Some(hex) if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+            let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+            Ok(Color::TrueColor {
+                r: channel(0)?,
+                g: channel(2)?,
+                b: channel(4)?,
+            })
+        }
+        Some(_) => Err(invalid()),
+        None => s.parse().map_err(|_| invalid()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::sync::Mutex;
+
+    /// Serialises tests that force colour on, as the override is global.
+    static COLOR_OVERRIDE: Mutex<()> = Mutex::new(());
+
+    /// Run `f` with colour forced on.
+    pub fn with_color<T>(f: impl FnOnce() -> T) -> T {
+        let _guard = COLOR_OVERRIDE
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        colored::control::set_override(true);
+        let result = f();
+        colored::control::unset_override();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::with_color;
+    use super::*;
+    use crate::client::testing::transaction;
+
+    #[test]
+    fn custom_out_colour_applies_to_out_transactions() {
+        let theme = Theme {
+            outgoing: Color::Blue,
+            ..Theme::default()
+        };
+        let out = transaction("Tesco", "2021-12-01T10:00:00Z", "OUT", 1250, "GBP");
+        let incoming = transaction("Employer", "2021-12-01T10:00:00Z", "IN", 1250, "GBP");
+
+        let (out, incoming) = with_color(|| {
+            (
+                theme.format_transaction(&out),
+                theme.format_transaction(&incoming),
+            )
+        });
+
+        assert!(out.contains("\u{1b}[34m"));
+        assert!(!out.contains("\u{1b}[31m"));
+        assert!(!incoming.contains("\u{1b}[34m"));
+    }
+
+    #[test]
+    fn default_header_is_bold_without_colour() {
+        let header = with_color(|| Theme::default().header("ACCOUNT"));
+
+        assert_eq!(header, "\u{1b}[1mACCOUNT\u{1b}[0m");
+    }
+
+    #[test]
+    fn parse_color_accepts_names_and_hex() {
+        assert_eq!(parse_color("blue"), Ok(Color::Blue));
+        assert_eq!(parse_color("bright blue"), Ok(Color::BrightBlue));
+        assert_eq!(
+            parse_color("#0072b2"),
+            Ok(Color::TrueColor {
+                r: 0,
+                g: 114,
+                b: 178
+            })
+        );
+    }
+
+    #[test]
+    fn parse_color_rejects_bad_input() {
+        for bad in ["", "blurple", "#0072b", "#0072b2ff", "#00g2b2", "0072b2"] {
+            assert_eq!(parse_color(bad), Err(format!("invalid colour '{}'", bad)));
+        }
+    }
+
+    /// An empty directory for this test, unique to the test process.
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "test_starling-theme-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("Couldn't create test directory");
+        dir
+    }
+
+    #[test]
+    fn load_without_theme_file_is_builtin() {
+        let dir = temp_dir("missing");
+        assert_eq!(
+            Theme::load(BuiltinTheme::Colourblind, &dir),
+            Ok(BuiltinTheme::Colourblind.theme())
+        );
+    }
+
+    #[test]
+    fn load_applies_overrides() {
+        let dir = temp_dir("overrides");
+        std::fs::write(dir.join(THEME_FILE), "out: blue\nheader: '#0072b2'\n")
+            .expect("Couldn't write theme file");
+
+        let theme = Theme::load(BuiltinTheme::Default, &dir).expect("Couldn't load theme");
+        assert_eq!(theme.outgoing, Color::Blue);
+        assert_eq!(theme.incoming, Color::Green);
+        assert_eq!(
+            theme.header,
+            Some(Color::TrueColor {
+                r: 0,
+                g: 114,
+                b: 178
+            })
+        );
+    }
+
+    #[test]
+    fn load_reports_unreadable_theme_file() {
+        // A directory where the file should be can't be read as a theme.
+        let dir = temp_dir("unreadable");
+        std::fs::create_dir(dir.join(THEME_FILE)).expect("Couldn't create directory");
+
+        let error = Theme::load(BuiltinTheme::Default, &dir).expect_err("Loaded a directory");
+        assert!(error.starts_with(THEME_FILE));
+    }
+}